@@ -38,10 +38,12 @@ extern crate magic_sys as ffi;
 extern crate bitflags;
 
 use errno::errno;
-use libc::size_t;
+use libc::{c_int, c_void, size_t};
 use std::error;
 use std::ffi::{CStr, CString};
 use std::fmt::Display;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::str;
 
@@ -51,6 +53,22 @@ macro_rules! from_c_str_unsafe {
     };
 }
 
+/// Splits a `libmagic` result produced with `CONTINUE` set into its
+/// individual matches, trimming each one.
+fn split_continued_matches(s: &str) -> Vec<String> {
+    s.split("\n- ").map(|part| part.trim().to_string()).collect()
+}
+
+/// Splits a `libmagic` result produced with `EXTENSION` set into its
+/// candidate extensions, or an empty `Vec` for libmagic's "unknown" placeholder.
+fn split_extensions(s: &str) -> Vec<String> {
+    if s == "???" {
+        Vec::new()
+    } else {
+        s.split('/').map(|part| part.to_string()).collect()
+    }
+}
+
 // Make it easier to use `MagicFlags::default()` and such
 pub use flags::MagicFlags;
 
@@ -181,6 +199,39 @@ pub mod flags {
     }
 }
 
+/// Indices into libmagic's per-cookie tunable parameters
+///
+/// Used with `Magic::set_parameter` and `Magic::get_parameter` to control
+/// how deeply and broadly libmagic probes a file.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MagicParam {
+    /// Limits how many levels of indirect magic rules are followed.
+    IndirMax = 0,
+
+    /// Limits the length of a name used in a named rule.
+    NameMax = 1,
+
+    /// Limits the number of ELF program headers read.
+    ElfPhnumMax = 2,
+
+    /// Limits the number of ELF section headers read.
+    ElfShnumMax = 3,
+
+    /// Limits the number of ELF notes read.
+    ElfNotesMax = 4,
+
+    /// Limits the number of matches performed for a regex-based rule.
+    RegexMax = 5,
+
+    /// Limits how many bytes of a file are read for magic analysis.
+    BytesMax = 6,
+
+    /// Limits how many bytes are looked at when determining the text encoding.
+    ///
+    /// Only available on newer versions of `libmagic`.
+    EncodingMax = 7,
+}
+
 /// Returns the version of this crate in the format `MAJOR.MINOR.PATCH`.
 pub fn version() -> &'static str {
     env!("CARGO_PKG_VERSION")
@@ -189,10 +240,37 @@ pub fn version() -> &'static str {
     // TODO: There's also an optional _PRE part
 }
 
+/// Returns the path to the system's default magic database, as `libmagic` locates it
+///
+/// This is what `libmagic` falls back to when no databases are passed to
+/// `Magic::new`, so callers can discover it instead of hardcoding a path
+/// like `/usr/share/misc/magic`.
+pub fn default_database_path() -> String {
+    let p = unsafe { ffi::magic_getpath(ptr::null(), 0) };
+    if p.is_null() {
+        String::new()
+    } else {
+        from_c_str_unsafe!(p)
+    }
+}
+
 /// The error type used in this crate
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct MagicError {
     pub desc: String,
+
+    /// The `errno` `libmagic` had recorded when the error occurred, if any
+    pub errno: Option<i32>,
+}
+
+impl MagicError {
+    /// Returns the `errno` `libmagic` had recorded when the error occurred, if any
+    ///
+    /// This lets callers distinguish error causes programmatically, e.g.
+    /// telling "file not found" apart from "permission denied".
+    pub fn errno(&self) -> Option<i32> {
+        self.errno
+    }
 }
 
 impl error::Error for MagicError {
@@ -227,15 +305,22 @@ impl Magic {
         } else {
             Some(MagicError {
                 desc: from_c_str_unsafe!(e),
+                errno: None,
             })
         }
     }
 
     fn magic_failure(&self) -> MagicError {
+        let errno = unsafe { ffi::magic_errno(self.cookie) };
+        let errno = if errno == 0 { None } else { Some(errno) };
         match self.last_error() {
-            Some(e) => e,
+            Some(mut e) => {
+                e.errno = errno;
+                e
+            }
             None => MagicError {
                 desc: String::from("unknown error"),
+                errno,
             },
         }
     }
@@ -244,6 +329,7 @@ impl Magic {
     pub fn file(&self, filename: &str) -> Result<String, MagicError> {
         let f = CString::new(filename).map_err(|e| MagicError {
             desc: format!("{:?}", e),
+            errno: None,
         })?;
         let cf = f.as_ptr();
         let s = unsafe { ffi::magic_file(self.cookie, cf) };
@@ -266,6 +352,94 @@ impl Magic {
         }
     }
 
+    /// Returns a textual description of the contents of the open file descriptor `fd`
+    ///
+    /// Unlike `file`, this doesn't need a path and so works with pipes,
+    /// sockets and other handles that were never opened by name. Note that
+    /// `libmagic` reads from `fd` to do its analysis, leaving its offset at
+    /// EOF; seek it back yourself if you need to read `fd` again afterwards.
+    pub fn descriptor(&self, fd: RawFd) -> Result<String, MagicError> {
+        let s = unsafe { ffi::magic_descriptor(self.cookie, fd) };
+        if s.is_null() {
+            Err(self.magic_failure())
+        } else {
+            Ok(from_c_str_unsafe!(s))
+        }
+    }
+
+    /// Returns a textual description of the contents of the open file handle `f`
+    ///
+    /// A convenience wrapper around `descriptor` for anything that implements
+    /// `AsRawFd`, e.g. `std::fs::File`. See `descriptor` for the caveat about
+    /// `f`'s offset being left at EOF.
+    pub fn file_handle<F: AsRawFd>(&self, f: &F) -> Result<String, MagicError> {
+        self.descriptor(f.as_raw_fd())
+    }
+
+    /// Returns a textual description of every magic rule that matches the contents of `filename`
+    ///
+    /// Sets the `CONTINUE` flag (overwriting any previously set flags, as
+    /// `set_flags` does) so `libmagic` reports every matching rule instead of
+    /// just the strongest one, then splits its `"\n- "`-separated result into
+    /// one `String` per match.
+    pub fn file_all(&self, filename: &str) -> Result<Vec<String>, MagicError> {
+        self.set_flags(flags::MagicFlags::CONTINUE);
+        self.file(filename).map(|s| split_continued_matches(&s))
+    }
+
+    /// Returns a textual description of every magic rule that matches the contents of `buf`
+    ///
+    /// See `file_all` for details on the `CONTINUE` flag and match separator.
+    pub fn buffer_all(&self, buf: &[u8]) -> Result<Vec<String>, MagicError> {
+        self.set_flags(flags::MagicFlags::CONTINUE);
+        self.buffer(buf).map(|s| split_continued_matches(&s))
+    }
+
+    /// Returns the candidate file extensions for the contents of `filename`
+    ///
+    /// Sets the `EXTENSION` flag (overwriting any previously set flags, as
+    /// `set_flags` does) and splits libmagic's `/`-separated result into one
+    /// `String` per candidate, most canonical first. Returns an empty `Vec`
+    /// when libmagic doesn't recognize the file, signalled by its `"???"`
+    /// placeholder.
+    pub fn extensions(&self, filename: &str) -> Result<Vec<String>, MagicError> {
+        self.set_flags(flags::MagicFlags::EXTENSION);
+        self.file(filename).map(|s| split_extensions(&s))
+    }
+
+    /// Returns the candidate file extensions for the contents of `buf`
+    ///
+    /// See `extensions` for details on the `EXTENSION` flag and placeholder value.
+    pub fn buffer_extensions(&self, buf: &[u8]) -> Result<Vec<String>, MagicError> {
+        self.set_flags(flags::MagicFlags::EXTENSION);
+        self.buffer(buf).map(|s| split_extensions(&s))
+    }
+
+    /// Suggests a corrected file extension for `path`, based on its contents
+    ///
+    /// Returns `Some(new_path)` with `path`'s extension replaced by the first
+    /// candidate from `extensions` when `path`'s current extension isn't
+    /// among the valid candidates. Returns `None` when the current extension
+    /// is already acceptable, or when libmagic doesn't recognize the file.
+    pub fn suggested_rename(&self, path: &Path) -> Result<Option<PathBuf>, MagicError> {
+        let filename = path.to_str().ok_or_else(|| MagicError {
+            desc: format!("path is not valid UTF-8: {:?}", path),
+            errno: None,
+        })?;
+
+        let candidates = self.extensions(filename)?;
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let current_ext = path.extension().and_then(|ext| ext.to_str());
+        if current_ext.map_or(false, |ext| candidates.iter().any(|c| c == ext)) {
+            return Ok(None);
+        }
+
+        Ok(Some(path.with_extension(&candidates[0])))
+    }
+
     /*
     // Returns a textual explanation of the last error, if any
     //
@@ -289,6 +463,40 @@ impl Magic {
         unsafe { ffi::magic_setflags(self.cookie, flags.bits()) != -1 }
     }
 
+    /// Sets the value of a tunable `libmagic` parameter
+    pub fn set_parameter(&self, param: MagicParam, value: usize) -> Result<(), MagicError> {
+        let value = value as size_t;
+        let rv = unsafe {
+            ffi::magic_setparam(
+                self.cookie,
+                param as c_int,
+                &value as *const size_t as *const c_void,
+            )
+        };
+        if rv == 0 {
+            Ok(())
+        } else {
+            Err(self.magic_failure())
+        }
+    }
+
+    /// Returns the value of a tunable `libmagic` parameter
+    pub fn get_parameter(&self, param: MagicParam) -> Result<usize, MagicError> {
+        let mut value: size_t = 0;
+        let rv = unsafe {
+            ffi::magic_getparam(
+                self.cookie,
+                param as c_int,
+                &mut value as *mut size_t as *mut c_void,
+            )
+        };
+        if rv == 0 {
+            Ok(value as usize)
+        } else {
+            Err(self.magic_failure())
+        }
+    }
+
     // TODO: check, compile, list and load mostly do the same, refactor!
     // TODO: ^ also needs to implement multiple databases, possibly waiting for the Path reform
 
@@ -300,6 +508,7 @@ impl Magic {
         } else {
             cstring = CString::new(filenames.join(":")).map_err(|e| MagicError {
                 desc: format!("{:?}", e),
+                errno: None,
             })?;
             cstring.as_ptr()
         };
@@ -325,6 +534,7 @@ impl Magic {
         } else {
             cstring = CString::new(filenames.join(":")).map_err(|e| MagicError {
                 desc: format!("{:?}", e),
+                errno: None,
             })?;
             cstring.as_ptr()
         };
@@ -344,6 +554,7 @@ impl Magic {
         } else {
             cstring = CString::new(filenames.join(":")).map_err(|e| MagicError {
                 desc: format!("{:?}", e),
+                errno: None,
             })?;
             cstring.as_ptr()
         };
@@ -365,6 +576,7 @@ impl Magic {
         } else {
             cstring = CString::new(filenames.join(":")).map_err(|e| MagicError {
                 desc: format!("{:?}", e),
+                errno: None,
             })?;
             cstring.as_ptr()
         };
@@ -406,6 +618,7 @@ impl Magic {
             let e = errno();
             Err(MagicError {
                 desc: format!("{} ({})", e, e.0),
+                errno: Some(e.0),
             })
         } else {
             Ok(Magic { cookie })
@@ -434,6 +647,108 @@ impl Magic {
     }
 }
 
+/// A thread-safe wrapper around `Magic`
+///
+/// `libmagic` cookies are not reentrant, so a bare `Magic` is neither `Send`
+/// nor `Sync`. `SyncMagic` owns a `Magic` behind a `std::sync::Mutex`,
+/// serializing access so the cookie is only ever touched by one thread at a
+/// time, and re-exposes its query, flag and parameter methods by locking
+/// around them. `check`, `compile` and `list` are not re-exposed.
+pub struct SyncMagic {
+    inner: std::sync::Mutex<Magic>,
+}
+
+// Safety: every access to the wrapped `Magic` (and therefore to its raw
+// `ffi::Magic` cookie) goes through the `Mutex`, which guarantees at most
+// one thread touches the cookie at a time. `libmagic` only requires that a
+// cookie not be used concurrently from multiple threads; it doesn't need to
+// stay pinned to the thread that created it, so handing it between threads
+// one lock-guard at a time is sound.
+unsafe impl Send for SyncMagic {}
+unsafe impl Sync for SyncMagic {}
+
+impl SyncMagic {
+    /// Creates a new configuration and loads one or more magic databases
+    ///
+    /// See `Magic::new` for details.
+    pub fn new(flags: flags::MagicFlags, filenames: &[&str]) -> Result<SyncMagic, MagicError> {
+        Magic::new(flags, filenames).map(|magic| SyncMagic {
+            inner: std::sync::Mutex::new(magic),
+        })
+    }
+
+    /// Creates a new configuration and loads one or more buffers
+    ///
+    /// See `Magic::new_from_buffers` for details.
+    pub fn new_from_buffers(flags: flags::MagicFlags, buffers: &[&[u8]]) -> Result<SyncMagic, MagicError> {
+        Magic::new_from_buffers(flags, buffers).map(|magic| SyncMagic {
+            inner: std::sync::Mutex::new(magic),
+        })
+    }
+
+    /// Returns a textual description of the contents of the `filename`
+    pub fn file(&self, filename: &str) -> Result<String, MagicError> {
+        self.inner.lock().unwrap().file(filename)
+    }
+
+    /// Returns a textual description of the contents of the `buffer`
+    pub fn buffer(&self, buf: &[u8]) -> Result<String, MagicError> {
+        self.inner.lock().unwrap().buffer(buf)
+    }
+
+    /// Sets the flags to use
+    ///
+    /// Overwrites any previously set flags, e.g. those from `load()`.
+    pub fn set_flags(&self, flags: flags::MagicFlags) -> bool {
+        self.inner.lock().unwrap().set_flags(flags)
+    }
+
+    /// Sets the value of a tunable `libmagic` parameter
+    pub fn set_parameter(&self, param: MagicParam, value: usize) -> Result<(), MagicError> {
+        self.inner.lock().unwrap().set_parameter(param, value)
+    }
+
+    /// Returns the value of a tunable `libmagic` parameter
+    pub fn get_parameter(&self, param: MagicParam) -> Result<usize, MagicError> {
+        self.inner.lock().unwrap().get_parameter(param)
+    }
+
+    /// Returns a textual description of the contents of the open file descriptor `fd`
+    pub fn descriptor(&self, fd: RawFd) -> Result<String, MagicError> {
+        self.inner.lock().unwrap().descriptor(fd)
+    }
+
+    /// Returns a textual description of the contents of the open file handle `f`
+    pub fn file_handle<F: AsRawFd>(&self, f: &F) -> Result<String, MagicError> {
+        self.inner.lock().unwrap().file_handle(f)
+    }
+
+    /// Returns a textual description of every magic rule that matches the contents of `filename`
+    pub fn file_all(&self, filename: &str) -> Result<Vec<String>, MagicError> {
+        self.inner.lock().unwrap().file_all(filename)
+    }
+
+    /// Returns a textual description of every magic rule that matches the contents of `buf`
+    pub fn buffer_all(&self, buf: &[u8]) -> Result<Vec<String>, MagicError> {
+        self.inner.lock().unwrap().buffer_all(buf)
+    }
+
+    /// Returns the candidate file extensions for the contents of `filename`
+    pub fn extensions(&self, filename: &str) -> Result<Vec<String>, MagicError> {
+        self.inner.lock().unwrap().extensions(filename)
+    }
+
+    /// Returns the candidate file extensions for the contents of `buf`
+    pub fn buffer_extensions(&self, buf: &[u8]) -> Result<Vec<String>, MagicError> {
+        self.inner.lock().unwrap().buffer_extensions(buf)
+    }
+
+    /// Suggests a corrected file extension for `path`, based on its contents
+    pub fn suggested_rename(&self, path: &Path) -> Result<Option<PathBuf>, MagicError> {
+        self.inner.lock().unwrap().suggested_rename(path)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate regex;
@@ -475,6 +790,47 @@ mod tests {
         assert_eq!(magic.buffer(s).ok().unwrap(), "text/x-python");
     }
 
+    #[test]
+    fn file_all() {
+        let magic = Magic::new(flags::MagicFlags::NONE, &vec!["data/tests/db-images-png"]).unwrap();
+
+        let path = "data/tests/rust-logo-128x128-blk.png";
+
+        assert_eq!(
+            magic.file_all(&path).ok().unwrap(),
+            vec!["PNG image data, 128 x 128, 8-bit colormap, non-interlaced"]
+        );
+    }
+
+    #[test]
+    fn buffer_all() {
+        let magic = Magic::new(flags::MagicFlags::NONE, &vec!["data/tests/db-python"]).unwrap();
+
+        let s = b"#!/usr/bin/env python\nprint('Hello, world!')";
+        assert_eq!(
+            magic.buffer_all(s).ok().unwrap(),
+            vec!["Python script, ASCII text executable"]
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        let magic = Magic::new(flags::MagicFlags::NONE, &vec!["data/tests/db-images-png"]).unwrap();
+
+        let path = "data/tests/rust-logo-128x128-blk.png";
+        assert_eq!(magic.extensions(&path).ok().unwrap(), vec!["png"]);
+    }
+
+    #[test]
+    fn suggested_rename() {
+        use std::path::PathBuf;
+
+        let magic = Magic::new(flags::MagicFlags::NONE, &vec!["data/tests/db-images-png"]).unwrap();
+
+        let right_ext = PathBuf::from("data/tests/rust-logo-128x128-blk.png");
+        assert_eq!(magic.suggested_rename(&right_ext).ok().unwrap(), None);
+    }
+
     #[test]
     fn file_error() {
         let magic = Magic::new(flags::MagicFlags::NONE | flags::MagicFlags::ERROR, &[]).unwrap();